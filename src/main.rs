@@ -1,26 +1,59 @@
+mod providers;
+mod results;
+
 use clap::{Arg, Command};
-use openai::{
-    chat::{self, ChatCompletionMessage, ChatCompletionMessageRole},
-    set_key,
-};
+use providers::{LlmClient, Message, Provider};
 use reqwest::{Client, ClientBuilder};
+use results::{failure_report, parse_test_cases, RunResponse};
 use scraper::{Html, Selector};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, error::Error, sync::Arc};
+use std::{collections::HashMap, error::Error, sync::Arc, time::Duration};
+use tokio::sync::Semaphore;
+
+// Spawned tasks require their errors to be `Send` to cross the await/task
+// boundary, so every fallible function in this crate shares this
+// `Send + Sync` error type rather than the plain `Box<dyn Error>` a non-concurrent CLI
+// could get away with.
+pub(crate) type AppError = Box<dyn Error + Send + Sync>;
 
 // Config for the cli
+//
+// Every field added after the original `students: Vec<Student>` is
+// `#[serde(default)]`: `confy::load` deserializes with strict
+// `toml::from_str`, which errors on a missing key rather than falling back
+// to `Default`, so without this a config file saved before a given field
+// existed would fail to parse at all and break the CLI for every subcommand.
 #[derive(Serialize, Deserialize)]
 struct MyConfig {
-    openai_key: String,
     students: Vec<Student>,
+    #[serde(default)]
+    providers: Vec<NamedProvider>,
+    #[serde(default)]
+    active_provider: Option<String>,
+    #[serde(default)]
+    extra: ExtraConfig,
+    #[serde(default)]
+    roles: Vec<Role>,
 }
 
 /// `MyConfig` implements `Default`
 impl ::std::default::Default for MyConfig {
     fn default() -> Self {
         Self {
-            openai_key: "".into(),
             students: Default::default(),
+            providers: Default::default(),
+            active_provider: None,
+            extra: Default::default(),
+            roles: vec![
+                Role {
+                    name: "java".into(),
+                    prompt: "Solve the provided problem by editing the provided java method. Only respond with the unformatted code and nothing else.".into(),
+                },
+                Role {
+                    name: "python".into(),
+                    prompt: "Solve the provided problem by editing the provided python function. Only respond with the unformatted code and nothing else.".into(),
+                },
+            ],
         }
     }
 }
@@ -32,9 +65,50 @@ struct Student {
     pass: String,
 }
 
-// Send an openai api request to get the solution code
-async fn solve_prob(client: &Client, prob: &str) -> String {
-    // Parse the html of the problem page
+// A provider configuration, named so it can be selected with `setprovider`
+#[derive(Serialize, Deserialize, Clone)]
+struct NamedProvider {
+    name: String,
+    provider: Provider,
+}
+
+// A named system prompt preset, selectable per run with `solve --role`
+#[derive(Serialize, Deserialize, Clone)]
+struct Role {
+    name: String,
+    prompt: String,
+}
+
+// Proxy/timeout settings shared by every outbound HTTP client. `proxy` is
+// optional since reqwest already honors `HTTPS_PROXY`/`ALL_PROXY` by default;
+// setting it here overrides the environment for a fixed proxy.
+#[derive(Serialize, Deserialize, Clone, Default)]
+struct ExtraConfig {
+    proxy: Option<String>,
+    connect_timeout_secs: Option<u64>,
+    timeout_secs: Option<u64>,
+    concurrency: Option<usize>,
+}
+
+// Build an HTTP client honoring the configured proxy/timeouts
+fn build_client(cookie_store: bool, extra: &ExtraConfig) -> Result<Client, AppError> {
+    let mut builder = ClientBuilder::new().cookie_store(cookie_store);
+
+    if let Some(proxy) = &extra.proxy {
+        builder = builder.proxy(reqwest::Proxy::all(proxy)?);
+    }
+    if let Some(secs) = extra.connect_timeout_secs {
+        builder = builder.connect_timeout(Duration::from_secs(secs));
+    }
+    if let Some(secs) = extra.timeout_secs {
+        builder = builder.timeout(Duration::from_secs(secs));
+    }
+
+    Ok(builder.build()?)
+}
+
+// Scrape a codingbat problem page for its description and example method
+async fn fetch_problem(client: &Client, prob: &str) -> (String, String) {
     let res = client
         .get(format!("https://codingbat.com/prob/{}", prob))
         .send()
@@ -50,60 +124,167 @@ async fn solve_prob(client: &Client, prob: &str) -> String {
         .expect("Could not parse problem")
         .text()
         .collect::<Vec<_>>()[0]
-        .trim();
+        .trim()
+        .to_string();
     let excode = document
         .select(&excode_sel)
         .next()
         .expect("Could not parse example code")
         .text()
         .collect::<Vec<_>>()[0]
-        .trim();
-
-    // Build the starting message
-    let sys_message = ChatCompletionMessage{
-        role: ChatCompletionMessageRole::System,
-        content: "Solve the provided problem by editing the provided java method. Only respond with the unformatted code and nothing else.".to_string(),
-        name: None
-    };
-
-    // Build the problem message
-    let user_message = ChatCompletionMessage {
-        role: chat::ChatCompletionMessageRole::User,
-        content: format!("{}\n{}", problem, excode),
-        name: None,
-    };
-
-    // Send the request to the openai api
-    chat::ChatCompletion::builder("gpt-3.5-turbo", vec![sys_message, user_message])
-        .create()
-        .await
-        .unwrap()
-        .unwrap()
-        .choices
-        .first()
-        .unwrap()
-        .message
-        .content
-        .clone()
+        .trim()
+        .to_string();
+
+    (problem, excode)
 }
 
-// Send a post request to run the code
-async fn run_code(client: &Client, prob: &str, code: &str, cuname: &str) {
-    let mut form_data = HashMap::new();
-    form_data.insert("id", prob);
-    form_data.insert("code", code);
-    form_data.insert("cuname", cuname);
+// Outcome of the solve/submit/retry loop for a single problem
+enum ProblemStatus {
+    Passed { attempts: u32 },
+    Failed { attempts: u32 },
+}
 
-    client
-        .post("https://codingbat.com/run")
-        .form(&form_data)
-        .send()
-        .await
-        .expect("Failed to send run request");
+// Solve a problem, submit it, and if any test cases fail, feed the model the
+// failing cases and retry (carrying the conversation history forward), making
+// at most `max_attempts` attempts in all. Under `--verbose`, prints a progress line per phase
+// plus the generated code as it streams in.
+async fn solve_prob(
+    client: &Client,
+    llm: &dyn LlmClient,
+    prob: &str,
+    max_attempts: u32,
+    verbose: bool,
+    system_prompt: &str,
+) -> Result<(String, ProblemStatus), AppError> {
+    if verbose {
+        println!("[{}] scraping problem", prob);
+    }
+    let (problem, excode) = fetch_problem(client, prob).await;
+
+    let mut messages = vec![
+        Message::system(system_prompt),
+        Message::user(format!("{}\n{}", problem, excode)),
+    ];
+
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        if verbose {
+            println!("[{}] generating (attempt {}/{})", prob, attempt, max_attempts);
+        }
+
+        let mut on_token: Box<dyn for<'a> FnMut(&'a str)> = if verbose {
+            Box::new(|token: &str| {
+                print!("{}", token);
+                let _ = std::io::Write::flush(&mut std::io::stdout());
+            })
+        } else {
+            Box::new(|_: &str| {})
+        };
+        let code = llm.complete(client, &messages, &mut *on_token).await?;
+        if verbose {
+            println!();
+        }
+        messages.push(Message::assistant(code.clone()));
+
+        if verbose {
+            println!("[{}] submitting", prob);
+        }
+        // Probe the solution anonymously (no cuname) before handing it off
+        // to the per-student submission pass
+        let test_cases = run_code(client, prob, &code, "").await?;
+        // `all()` on an empty iterator is vacuously true, so an empty
+        // `test_cases` (a compile error, or output CodingBat returned in a
+        // shape `parse_test_cases` doesn't recognize) must not read as a pass.
+        if !test_cases.is_empty() && test_cases.iter().all(|case| case.passed) {
+            if verbose {
+                println!("[{}] result: passed", prob);
+            }
+            return Ok((code, ProblemStatus::Passed { attempts: attempt }));
+        }
+
+        if attempt >= max_attempts {
+            if verbose {
+                println!("[{}] result: failed", prob);
+            }
+            return Ok((code, ProblemStatus::Failed { attempts: attempt }));
+        }
+
+        messages.push(Message::user(failure_report(&test_cases)));
+    }
+}
+
+const RETRY_ATTEMPTS: u32 = 3;
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+
+// Retry a transient-failure-prone operation with exponential backoff and
+// jitter: 3 attempts total, delay doubling from ~500ms. `retryable` decides
+// whether a given error is worth retrying at all — callers whose op isn't
+// idempotent should only call this retryable for errors that could not have
+// reached the other end (see `is_connect_failure` below).
+async fn with_retry<F, Fut, T>(retryable: fn(&AppError) -> bool, mut op: F) -> Result<T, AppError>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<T, AppError>>,
+{
+    let mut delay = RETRY_BASE_DELAY;
+    for attempt in 1..=RETRY_ATTEMPTS {
+        match op().await {
+            Ok(value) => return Ok(value),
+            Err(err) if attempt == RETRY_ATTEMPTS || !retryable(&err) => return Err(err),
+            Err(_) => {
+                let jitter = Duration::from_millis(rand::random::<u64>() % 250);
+                tokio::time::sleep(delay + jitter).await;
+                delay *= 2;
+            }
+        }
+    }
+    unreachable!("loop always returns on the final attempt")
+}
+
+// A submission to `/run` isn't idempotent: CodingBat counts it as a real
+// attempt for `cuname` once it arrives, so retrying anything that might have
+// already reached the server risks double-submitting the same code. Only
+// failures to establish the connection in the first place are retryable here
+// — `error_for_status()` (CodingBat rejected the request) and `.json()`
+// (the response didn't parse) both mean the server already saw it, and
+// retrying either would never produce a different outcome anyway.
+fn is_connect_failure(err: &AppError) -> bool {
+    err.downcast_ref::<reqwest::Error>().is_some_and(|e| e.is_connect())
+}
+
+// Send a post request to run the code, returning the parsed per-test-case results.
+// Connection failures are retried with exponential backoff; anything past
+// that point (a rejected submission, a malformed response) is returned
+// immediately rather than resubmitted.
+async fn run_code(
+    client: &Client,
+    prob: &str,
+    code: &str,
+    cuname: &str,
+) -> Result<Vec<results::TestCase>, AppError> {
+    with_retry(is_connect_failure, || async {
+        let mut form_data = HashMap::new();
+        form_data.insert("id", prob);
+        form_data.insert("code", code);
+        form_data.insert("cuname", cuname);
+
+        let response: RunResponse = client
+            .post("https://codingbat.com/run")
+            .form(&form_data)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(parse_test_cases(&response.output))
+    })
+    .await
 }
 
 // Logs the user in
-async fn login(client: &reqwest::Client, uname: &str, pass: &str) -> Result<(), Box<dyn Error>>{
+async fn login(client: &reqwest::Client, uname: &str, pass: &str) -> Result<(), AppError>{
     let mut form_data = HashMap::new();
     form_data.insert("uname", uname);
     form_data.insert("pw", pass);
@@ -118,7 +299,7 @@ async fn login(client: &reqwest::Client, uname: &str, pass: &str) -> Result<(),
 }
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
+async fn main() -> Result<(), AppError> {
     // Setup the cli
     let matches = Command::new("batgpt")
         .version("0.0.1")
@@ -129,7 +310,9 @@ async fn main() -> Result<(), Box<dyn Error>> {
             Arg::new("verbose")
                 .short('v')
                 .long("verbose")
-                .help("Enable verbose output"),
+                .help("Enable verbose output")
+                .action(clap::ArgAction::SetTrue)
+                .global(true),
         )
         .subcommand(
             Command::new("add")
@@ -157,22 +340,149 @@ async fn main() -> Result<(), Box<dyn Error>> {
         )
         .subcommand(Command::new("list").about("List all students"))
         .subcommand(
-            Command::new("setkey").about("Set the openai api key").arg(
-                Arg::new("key")
-                    .help("Sets the openai api key")
-                    .required(true)
-                    .index(1),
-            ),
+            Command::new("addprovider")
+                .about("Add an LLM provider configuration")
+                .arg(
+                    Arg::new("name")
+                        .help("Name to refer to this provider by")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("type")
+                        .help("Provider type")
+                        .required(true)
+                        .value_parser(["openai", "azure-openai", "openai-compatible"])
+                        .index(2),
+                )
+                .arg(
+                    Arg::new("api_key")
+                        .help("API key for the provider")
+                        .required(true)
+                        .index(3),
+                )
+                .arg(
+                    Arg::new("model")
+                        .help("Model (or Azure deployment) name")
+                        .required(true)
+                        .index(4),
+                )
+                .arg(
+                    Arg::new("base_url")
+                        .long("base-url")
+                        .help("API base URL (required for azure-openai/openai-compatible)"),
+                )
+                .arg(
+                    Arg::new("organization_id")
+                        .long("org")
+                        .help("Organization id (openai only)"),
+                ),
         )
+        .subcommand(Command::new("listproviders").about("List all configured providers"))
         .subcommand(
-            Command::new("solve").about("Solve a problem").arg(
-                Arg::new("prob")
-                    .help("Sets the problem to solve")
-                    .num_args(1..)
+            Command::new("setprovider").about("Select the active provider").arg(
+                Arg::new("name")
+                    .help("Name of the provider to activate")
                     .required(true)
                     .index(1),
             ),
         )
+        .subcommand(
+            Command::new("setmodel")
+                .about("Set the model used by the active provider")
+                .arg(
+                    Arg::new("model")
+                        .help("Model (or Azure deployment) name")
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            Command::new("setproxy")
+                .about("Set the proxy used by outbound requests (https/socks5 URL)")
+                .arg(
+                    Arg::new("url")
+                        .help("Proxy URL, or \"none\" to clear it and fall back to HTTPS_PROXY/ALL_PROXY")
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            Command::new("settimeout")
+                .about("Set connect/overall timeouts (seconds) for outbound requests")
+                .arg(
+                    Arg::new("connect_timeout")
+                        .help("Connect timeout in seconds")
+                        .value_parser(clap::value_parser!(u64))
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("timeout")
+                        .help("Overall request timeout in seconds")
+                        .value_parser(clap::value_parser!(u64))
+                        .required(true)
+                        .index(2),
+                ),
+        )
+        .subcommand(
+            Command::new("setconcurrency")
+                .about("Set the default max logins/submissions in flight at once")
+                .arg(
+                    Arg::new("concurrency")
+                        .help("Max concurrent logins/submissions")
+                        .value_parser(clap::value_parser!(usize))
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            Command::new("addrole")
+                .about("Add a named system prompt preset")
+                .arg(
+                    Arg::new("name")
+                        .help("Name to refer to this role by")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("prompt")
+                        .help("System prompt for this role")
+                        .required(true)
+                        .index(2),
+                ),
+        )
+        .subcommand(Command::new("listrole").about("List all configured roles"))
+        .subcommand(
+            Command::new("solve")
+                .about("Solve a problem")
+                .arg(
+                    Arg::new("prob")
+                        .help("Sets the problem to solve")
+                        .num_args(1..)
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("max_attempts")
+                        .long("max-attempts")
+                        .help("Maximum number of attempts per problem, counting the first one")
+                        .value_parser(clap::value_parser!(u32))
+                        .default_value("3"),
+                )
+                .arg(
+                    Arg::new("role")
+                        .long("role")
+                        .help("Name of the role (system prompt preset) to use")
+                        .default_value("java"),
+                )
+                .arg(
+                    Arg::new("concurrency")
+                        .long("concurrency")
+                        .help("Max logins/submissions in flight at once (defaults to the configured value, or 4)")
+                        .value_parser(clap::value_parser!(usize)),
+                ),
+        )
         .get_matches();
 
     // Load the config
@@ -186,90 +496,323 @@ async fn main() -> Result<(), Box<dyn Error>> {
                 pass: (add_matches.get_one::<String>("pass").unwrap()).clone(),
             });
 
-            confy::store(
-                "batgpt",
-                None,
-                &MyConfig {
-                    openai_key: config.openai_key,
-                    students,
-                },
-            )?;
+            confy::store("batgpt", None, &MyConfig { students, ..config })?;
         }
         Some(("remove", remove_matches)) => {
             let cuname = (remove_matches.get_one::<String>("cuname").unwrap()).clone();
             let mut students = config.students;
             students.retain(|student| student.cuname != cuname);
-            confy::store(
-                "batgpt",
-                None,
-                &MyConfig {
-                    openai_key: config.openai_key,
-                    students,
-                },
-            )?;
+            confy::store("batgpt", None, &MyConfig { students, ..config })?;
         }
         Some(("list", _)) => {
             for student in config.students {
                 println!("{}: {}", student.cuname, student.pass);
             }
         }
-        Some(("setkey", setkey_matches)) => {
+        Some(("addprovider", addprovider_matches)) => {
+            let name = (addprovider_matches.get_one::<String>("name").unwrap()).clone();
+            let api_key = (addprovider_matches.get_one::<String>("api_key").unwrap()).clone();
+            let model = (addprovider_matches.get_one::<String>("model").unwrap()).clone();
+            let base_url = addprovider_matches.get_one::<String>("base_url").cloned();
+            let organization_id = addprovider_matches.get_one::<String>("organization_id").cloned();
+
+            let provider = match addprovider_matches.get_one::<String>("type").unwrap().as_str() {
+                "openai" => Provider::OpenAi { api_key, model, organization_id },
+                "azure-openai" => {
+                    if organization_id.is_some() {
+                        return Err("--org is only valid with the openai provider type".into());
+                    }
+                    Provider::AzureOpenai {
+                        api_key,
+                        api_base: base_url.ok_or("azure-openai requires --base-url")?,
+                        model,
+                    }
+                }
+                "openai-compatible" => {
+                    if organization_id.is_some() {
+                        return Err("--org is only valid with the openai provider type".into());
+                    }
+                    Provider::OpenAiCompatible {
+                        api_key,
+                        base_url: base_url.ok_or("openai-compatible requires --base-url")?,
+                        model,
+                    }
+                }
+                _ => unreachable!("value_parser restricts provider type"),
+            };
+
+            let mut providers = config.providers;
+            providers.retain(|p| p.name != name);
+            providers.push(NamedProvider { name, provider });
+            confy::store("batgpt", None, &MyConfig { providers, ..config })?;
+        }
+        Some(("listproviders", _)) => {
+            for named in &config.providers {
+                let active = if config.active_provider.as_deref() == Some(&named.name) { " (active)" } else { "" };
+                println!("{}: {} [{}]{}", named.name, named.provider.model(), named.provider.type_name(), active);
+            }
+        }
+        Some(("setprovider", setprovider_matches)) => {
+            let name = (setprovider_matches.get_one::<String>("name").unwrap()).clone();
+            if !config.providers.iter().any(|p| p.name == name) {
+                return Err(format!("No provider named '{}', add one with addprovider first", name).into());
+            }
+            confy::store(
+                "batgpt",
+                None,
+                &MyConfig { active_provider: Some(name), ..config },
+            )?;
+        }
+        Some(("setmodel", setmodel_matches)) => {
+            let model = (setmodel_matches.get_one::<String>("model").unwrap()).clone();
+            let active = config.active_provider.clone().ok_or("No active provider, set one with setprovider")?;
+            let mut providers = config.providers;
+            let named = providers
+                .iter_mut()
+                .find(|p| p.name == active)
+                .ok_or("Active provider no longer exists")?;
+            named.provider.set_model(model);
+            confy::store("batgpt", None, &MyConfig { providers, ..config })?;
+        }
+        Some(("setproxy", setproxy_matches)) => {
+            let url = setproxy_matches.get_one::<String>("url").unwrap();
+            let proxy = if url == "none" { None } else { Some(url.clone()) };
+            confy::store(
+                "batgpt",
+                None,
+                &MyConfig { extra: ExtraConfig { proxy, ..config.extra.clone() }, ..config },
+            )?;
+        }
+        Some(("settimeout", settimeout_matches)) => {
+            let connect_timeout_secs = Some(*settimeout_matches.get_one::<u64>("connect_timeout").unwrap());
+            let timeout_secs = Some(*settimeout_matches.get_one::<u64>("timeout").unwrap());
             confy::store(
                 "batgpt",
                 None,
                 &MyConfig {
-                    openai_key: (setkey_matches.get_one::<String>("key").unwrap()).clone(),
-                    students: config.students,
+                    extra: ExtraConfig { connect_timeout_secs, timeout_secs, ..config.extra.clone() },
+                    ..config
                 },
             )?;
         }
+        Some(("setconcurrency", setconcurrency_matches)) => {
+            let concurrency = Some(*setconcurrency_matches.get_one::<usize>("concurrency").unwrap());
+            confy::store(
+                "batgpt",
+                None,
+                &MyConfig { extra: ExtraConfig { concurrency, ..config.extra.clone() }, ..config },
+            )?;
+        }
+        Some(("addrole", addrole_matches)) => {
+            let name = (addrole_matches.get_one::<String>("name").unwrap()).clone();
+            let prompt = (addrole_matches.get_one::<String>("prompt").unwrap()).clone();
+
+            let mut roles = config.roles;
+            roles.retain(|r| r.name != name);
+            roles.push(Role { name, prompt });
+            confy::store("batgpt", None, &MyConfig { roles, ..config })?;
+        }
+        Some(("listrole", _)) => {
+            for role in &config.roles {
+                println!("{}: {}", role.name, role.prompt);
+            }
+        }
         Some(("solve", solve_matches)) => {
             let mut handles = vec![];
 
-            // Set the openai api key
-            set_key(config.openai_key);
+            // Resolve the active provider
+            let active = config
+                .active_provider
+                .clone()
+                .ok_or("No active provider, set one with setprovider")?;
+            let provider = config
+                .providers
+                .iter()
+                .find(|p| p.name == active)
+                .ok_or("Active provider no longer exists")?
+                .provider
+                .clone();
+
+            // Resolve the selected role
+            let role_name = solve_matches.get_one::<String>("role").unwrap();
+            let system_prompt = config
+                .roles
+                .iter()
+                .find(|r| &r.name == role_name)
+                .ok_or_else(|| format!("No role named '{}', add one with addrole first", role_name))?
+                .prompt
+                .clone();
 
             // Create the parsing client
-            let parse_client = Client::new();
+            let parse_client = build_client(false, &config.extra)?;
+            let verbose = matches.get_flag("verbose");
 
             // Solve all the problems and store their solutions
+            let max_attempts = *solve_matches.get_one::<u32>("max_attempts").unwrap();
             let problems = solve_matches.get_many::<String>("prob").unwrap();
             let mut solutions: HashMap<String, String> = HashMap::new();
             for prob in problems {
-                let solution = solve_prob(&parse_client, &prob).await;
+                let (solution, status) =
+                    solve_prob(&parse_client, &provider, prob, max_attempts, verbose, &system_prompt).await?;
+                match status {
+                    ProblemStatus::Passed { attempts } => {
+                        println!("{}: passed after {} attempt(s)", prob, attempts)
+                    }
+                    ProblemStatus::Failed { attempts } => {
+                        println!("{}: failed after {} attempt(s)", prob, attempts)
+                    }
+                }
                 solutions.insert(prob.clone(), solution);
             }
 
             let shared_solutions = Arc::new(solutions);
 
+            // Bound how many logins/submissions are in flight at once so we
+            // don't hammer codingbat with one request per (student, problem)
+            let concurrency = solve_matches
+                .get_one::<usize>("concurrency")
+                .copied()
+                .or(config.extra.concurrency)
+                .unwrap_or(4);
+            let semaphore = Arc::new(Semaphore::new(concurrency));
+
             // Run the solution for each student
             for student in config.students {
-                // Create the client
-                let client = ClientBuilder::new()
-                    .cookie_store(true)
-                    .build()
-                    .expect("Failed to create client");
-
-                // Login
-                login(&client, &student.cuname, &student.pass).await?;
-
-                // Run the solutions asyncronously
-                let solutions = shared_solutions.as_ref().clone();
-                for (prob, solution) in solutions {
-                    let client = client.clone();
-                    let cuname = student.cuname.clone();
-                    handles.push(tokio::spawn(async move {
-                        run_code(&client, prob.as_str(), solution.as_str(), &cuname).await;
-                    }));
-                }
+                let client = build_client(true, &config.extra)?;
+                let semaphore = Arc::clone(&semaphore);
+                let solutions = Arc::clone(&shared_solutions);
+
+                handles.push(tokio::spawn(async move {
+                    let permit = Arc::clone(&semaphore).acquire_owned().await.expect("semaphore closed");
+                    if verbose {
+                        println!("logging in student {}", student.cuname);
+                    }
+                    login(&client, &student.cuname, &student.pass).await?;
+                    drop(permit);
+
+                    // Run the solutions concurrently, bounded by the same limiter
+                    let mut submit_handles = vec![];
+                    for (prob, solution) in solutions.as_ref().clone() {
+                        let client = client.clone();
+                        let cuname = student.cuname.clone();
+                        let semaphore = Arc::clone(&semaphore);
+                        submit_handles.push(tokio::spawn(async move {
+                            let _permit = semaphore.acquire_owned().await.expect("semaphore closed");
+                            let result = run_code(&client, &prob, &solution, &cuname).await;
+                            (prob, result)
+                        }));
+                    }
+
+                    let mut results = Vec::new();
+                    for handle in submit_handles {
+                        match handle.await {
+                            Ok(pair) => results.push(pair),
+                            Err(join_err) => {
+                                results.push(("<unknown problem>".to_string(), Err(Box::new(join_err) as AppError)))
+                            }
+                        }
+                    }
+                    Ok::<_, AppError>((student.cuname, results))
+                }));
             }
 
-            // Wait for all the tasks to finish
+            // Wait for all the tasks to finish, aggregating a summary instead
+            // of aborting the whole run on the first failure
+            let (mut successes, mut failures) = (0u32, 0u32);
             for handle in handles {
-                handle.await?;
+                match handle.await {
+                    Ok(Ok((cuname, results))) => {
+                        for (prob, result) in results {
+                            match result {
+                                Ok(_) => successes += 1,
+                                Err(err) => {
+                                    failures += 1;
+                                    eprintln!("{} ({}): submission failed: {}", cuname, prob, err);
+                                }
+                            }
+                        }
+                    }
+                    Ok(Err(err)) => {
+                        failures += 1;
+                        eprintln!("login failed: {}", err);
+                    }
+                    Err(join_err) => {
+                        failures += 1;
+                        eprintln!("task failed: {}", join_err);
+                    }
+                }
             }
+            println!("{} submission(s) succeeded, {} failed", successes, failures);
         }
         _ => {}
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod retry_tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[tokio::test]
+    async fn with_retry_returns_ok_without_retrying() {
+        let attempts = AtomicU32::new(0);
+        let result = with_retry(|_| true, || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Ok::<_, AppError>(42)
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(result, 42);
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn with_retry_retries_while_retryable_until_success() {
+        let attempts = AtomicU32::new(0);
+        let result = with_retry(|_| true, || async {
+            let attempt = attempts.fetch_add(1, Ordering::SeqCst) + 1;
+            if attempt < RETRY_ATTEMPTS {
+                Err::<u32, AppError>("transient".into())
+            } else {
+                Ok(attempt)
+            }
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(result, RETRY_ATTEMPTS);
+        assert_eq!(attempts.load(Ordering::SeqCst), RETRY_ATTEMPTS);
+    }
+
+    #[tokio::test]
+    async fn with_retry_returns_immediately_when_not_retryable() {
+        let attempts = AtomicU32::new(0);
+        let result = with_retry(|_| false, || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err::<u32, AppError>("permanent".into())
+        })
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn is_connect_failure_is_false_for_non_reqwest_errors() {
+        let err: AppError = "some other failure".into();
+        assert!(!is_connect_failure(&err));
+    }
+
+    #[tokio::test]
+    async fn is_connect_failure_is_true_for_a_refused_connection() {
+        // Port 0 always fails to connect (no listener can bind it), so this
+        // never touches the network and can't flake.
+        let client = Client::new();
+        let err = client.get("http://127.0.0.1:0").send().await.unwrap_err();
+        let err: AppError = Box::new(err);
+
+        assert!(is_connect_failure(&err));
+    }
+}
@@ -0,0 +1,209 @@
+use crate::AppError;
+use async_trait::async_trait;
+use eventsource_stream::Eventsource;
+use futures_util::StreamExt;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+// A backend capable of turning a conversation into a completion. Taking the
+// full message history (rather than a single system/user pair) lets callers
+// carry a multi-turn conversation forward, e.g. a solve/retry loop that feeds
+// the model its previous attempt and what went wrong with it.
+//
+// `complete` always streams the response over SSE and calls `on_token` as
+// each chunk arrives, so callers can surface incremental progress (e.g.
+// `--verbose`) while still getting the full completion back as the return
+// value. Implemented once against the chat-completions wire format so the
+// same request code works across OpenAI, Azure OpenAI, and any
+// OpenAI-compatible server (self-hosted, local models, etc).
+// `?Send` because `on_token` is a plain `&mut dyn FnMut`, not `Send` — callers
+// always drive this to completion on the current task rather than spawning it.
+#[async_trait(?Send)]
+pub trait LlmClient {
+    async fn complete(
+        &self,
+        client: &Client,
+        messages: &[Message],
+        on_token: &mut dyn for<'a> FnMut(&'a str),
+    ) -> Result<String, AppError>;
+}
+
+// One turn of a chat-completions conversation.
+#[derive(Clone)]
+pub struct Message {
+    pub role: Role,
+    pub content: String,
+}
+
+impl Message {
+    pub fn system(content: impl Into<String>) -> Self {
+        Self { role: Role::System, content: content.into() }
+    }
+
+    pub fn user(content: impl Into<String>) -> Self {
+        Self { role: Role::User, content: content.into() }
+    }
+
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self { role: Role::Assistant, content: content.into() }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+}
+
+impl Role {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Role::System => "system",
+            Role::User => "user",
+            Role::Assistant => "assistant",
+        }
+    }
+}
+
+// A single configured backend, selectable by name via `setprovider`.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "type")]
+pub enum Provider {
+    #[serde(rename = "openai")]
+    OpenAi {
+        api_key: String,
+        model: String,
+        organization_id: Option<String>,
+    },
+    #[serde(rename = "azure-openai")]
+    AzureOpenai {
+        api_key: String,
+        api_base: String,
+        model: String,
+    },
+    #[serde(rename = "openai-compatible")]
+    OpenAiCompatible {
+        api_key: String,
+        base_url: String,
+        model: String,
+    },
+}
+
+impl Provider {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Provider::OpenAi { .. } => "openai",
+            Provider::AzureOpenai { .. } => "azure-openai",
+            Provider::OpenAiCompatible { .. } => "openai-compatible",
+        }
+    }
+
+    pub fn model(&self) -> &str {
+        match self {
+            Provider::OpenAi { model, .. } => model,
+            Provider::AzureOpenai { model, .. } => model,
+            Provider::OpenAiCompatible { model, .. } => model,
+        }
+    }
+
+    pub fn set_model(&mut self, model: String) {
+        match self {
+            Provider::OpenAi { model: m, .. } => *m = model,
+            Provider::AzureOpenai { model: m, .. } => *m = model,
+            Provider::OpenAiCompatible { model: m, .. } => *m = model,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ChatMessage<'a> {
+    role: &'a str,
+    content: &'a str,
+}
+
+#[derive(Serialize)]
+struct ChatRequest<'a> {
+    model: &'a str,
+    messages: Vec<ChatMessage<'a>>,
+    stream: bool,
+}
+
+#[derive(Deserialize)]
+struct ChatChunk {
+    choices: Vec<ChatChunkChoice>,
+}
+
+#[derive(Deserialize)]
+struct ChatChunkChoice {
+    delta: ChatChunkDelta,
+}
+
+#[derive(Deserialize)]
+struct ChatChunkDelta {
+    content: Option<String>,
+}
+
+const OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+const AZURE_API_VERSION: &str = "2023-05-15";
+
+#[async_trait(?Send)]
+impl LlmClient for Provider {
+    async fn complete(
+        &self,
+        client: &Client,
+        messages: &[Message],
+        on_token: &mut dyn for<'a> FnMut(&'a str),
+    ) -> Result<String, AppError> {
+        let body = ChatRequest {
+            model: self.model(),
+            messages: messages
+                .iter()
+                .map(|m| ChatMessage { role: m.role.as_str(), content: &m.content })
+                .collect(),
+            stream: true,
+        };
+
+        let request = match self {
+            Provider::OpenAi { api_key, organization_id, .. } => {
+                let mut req = client
+                    .post(format!("{}/chat/completions", OPENAI_BASE_URL))
+                    .bearer_auth(api_key);
+                if let Some(org) = organization_id {
+                    req = req.header("OpenAI-Organization", org);
+                }
+                req
+            }
+            Provider::AzureOpenai { api_key, api_base, model } => client
+                .post(format!(
+                    "{}/openai/deployments/{}/chat/completions?api-version={}",
+                    api_base.trim_end_matches('/'),
+                    model,
+                    AZURE_API_VERSION
+                ))
+                .header("api-key", api_key),
+            Provider::OpenAiCompatible { api_key, base_url, .. } => client
+                .post(format!("{}/chat/completions", base_url.trim_end_matches('/')))
+                .bearer_auth(api_key),
+        };
+
+        let response = request.json(&body).send().await?.error_for_status()?;
+        let mut events = response.bytes_stream().eventsource();
+        let mut full = String::new();
+
+        while let Some(event) = events.next().await {
+            let event = event?;
+            if event.data == "[DONE]" {
+                break;
+            }
+
+            let chunk: ChatChunk = serde_json::from_str(&event.data)?;
+            if let Some(content) = chunk.choices.into_iter().next().and_then(|c| c.delta.content) {
+                on_token(&content);
+                full.push_str(&content);
+            }
+        }
+
+        Ok(full)
+    }
+}
@@ -0,0 +1,84 @@
+use scraper::Html;
+use serde::Deserialize;
+
+// CodingBat's `/run` endpoint returns a JSON envelope whose `output` field is
+// a list of HTML fragments, one per test case, each ending in "OK" (passed)
+// or carrying an expected/got mismatch (failed).
+#[derive(Deserialize)]
+pub struct RunResponse {
+    pub output: Vec<String>,
+}
+
+pub struct TestCase {
+    pub description: String,
+    pub passed: bool,
+}
+
+pub fn parse_test_cases(output: &[String]) -> Vec<TestCase> {
+    output
+        .iter()
+        .map(|fragment| {
+            let text = Html::parse_fragment(fragment)
+                .root_element()
+                .text()
+                .collect::<Vec<_>>()
+                .join(" ")
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ");
+
+            let passed = text.ends_with("OK");
+            TestCase { description: text, passed }
+        })
+        .collect()
+}
+
+// Builds a user message describing the failing cases so the model can see
+// exactly what its previous attempt got wrong.
+pub fn failure_report(cases: &[TestCase]) -> String {
+    let failing: Vec<&str> = cases
+        .iter()
+        .filter(|case| !case.passed)
+        .map(|case| case.description.as_str())
+        .collect();
+
+    format!(
+        "Your code failed the following test case(s):\n{}\nFix the code and respond with the full corrected method, unformatted and nothing else.",
+        failing.join("\n")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_test_cases_detects_pass_and_fail() {
+        let output = vec![
+            "<div>add(2, 3) → 5 OK</div>".to_string(),
+            "<div>add(1, 1) expected 2 got 3</div>".to_string(),
+        ];
+        let cases = parse_test_cases(&output);
+
+        assert_eq!(cases.len(), 2);
+        assert!(cases[0].passed);
+        assert!(!cases[1].passed);
+    }
+
+    #[test]
+    fn parse_test_cases_empty_output_is_empty() {
+        assert!(parse_test_cases(&[]).is_empty());
+    }
+
+    #[test]
+    fn failure_report_lists_only_failing_cases() {
+        let cases = vec![
+            TestCase { description: "case 1 OK".to_string(), passed: true },
+            TestCase { description: "case 2 expected 2 got 3".to_string(), passed: false },
+        ];
+        let report = failure_report(&cases);
+
+        assert!(report.contains("case 2 expected 2 got 3"));
+        assert!(!report.contains("case 1 OK"));
+    }
+}